@@ -2,6 +2,7 @@
 //!
 extern crate tokio_channel;
 extern crate tokio;
+extern crate futures;
 
 #[cfg(feature = "serde-impls")]
 #[macro_use]
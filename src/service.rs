@@ -14,8 +14,9 @@
 //!
 //! use std::collections::HashMap;
 //! use tokio_util::service;
-//! use tokio::prelude::*; 
-//! 
+//! use tokio_util::Never;
+//! use tokio::prelude::*;
+//!
 //!
 //! #[derive(Debug)]
 //! enum Op<K,V> {
@@ -30,7 +31,9 @@
 //! // wrap out setup logic in a closure to defer execution.
 //! let spawn_map = || {
 //!     let mut map = HashMap::new();
-//!     let handle = service::spawn(move |op| {
+//!     // the service is infallible, so its future resolves with `Never`
+//!     // as its error -- the worker can never be poisoned.
+//!     let handle = service::spawn(move |op| -> Result<_,Never> {
 //!         match op {
 //!             Op::Get(key) => Ok(map.get(key).cloned()),
 //!             Op::Set(key,val) => Ok(map.insert(key,val)),
@@ -61,10 +64,38 @@
 //!
 //! ```
 //!
+//! ## Backpressure
+//!
+//! `spawn` buffers calls in an unbounded queue, so a caller outpacing the map above
+//! would grow memory without limit. Use [`spawn_bounded`](fn.spawn_bounded.html)
+//! instead to cap the queue, and [`Handle::poll_ready`](struct.Handle.html#method.poll_ready)
+//! to wait for room before issuing a `call`.
+//!
+//! ## Batching
+//!
+//! When a service's per-call cost amortizes over many requests (crypto signature
+//! verification, DB multi-get), use [`spawn_batched`](fn.spawn_batched.html) to coalesce
+//! queued calls into a single `Vec<Req>`/`Vec<Rsp>` round trip instead of calling the
+//! service once per request.
+//!
+//! ## Concurrency limits
+//!
+//! Use [`spawn_limited`](fn.spawn_limited.html) to cap how many service futures may
+//! run at once, protecting a downstream resource (a connection pool, CPU-bound work)
+//! that the service guards. `Handle::call` waits for a permit to free up; `Handle::try_call`
+//! fails fast with `Error::Saturated` instead.
+//!
 use tokio_channel::{oneshot,mpsc};
+use tokio::timer::Delay;
 use tokio::prelude::*;
 use tokio;
-use std::{fmt,error};
+use futures::task::{self,Task};
+use futures::future::{self,Either};
+use std::collections::VecDeque;
+use std::sync::{Arc,Mutex};
+use std::time::{Duration,Instant};
+use std::{fmt,error,mem};
+use super::Never;
 
 
 /// Spawn a service to the event-loop.
@@ -72,19 +103,560 @@ use std::{fmt,error};
 /// Spawns a threadsafe service to the event-loop, returning a
 /// cloneable/sendable handle. See module-level docs for example usage.
 ///
+/// If the service future resolves to an error, the worker records it and
+/// every `Handle::call` not yet handed to the service, including later ones,
+/// resolves with `Error::Closed`, wrapping the failure that poisoned the worker.
+/// Calls the service was already running finish with their own result.
+///
+/// ```
+/// extern crate tokio_util;
+/// extern crate tokio;
+///
+/// use tokio_util::service::{self,Error};
+/// use tokio::prelude::*;
+///
+/// #[derive(Debug)]
+/// struct Oops;
+///
+/// impl std::fmt::Display for Oops {
+///     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result { f.write_str("oops") }
+/// }
+///
+/// impl std::error::Error for Oops { }
+///
+/// # fn main() {
+/// let spawn_failing = || Ok(service::spawn(move |_: ()| -> Result<(),Oops> { Err(Oops) }));
+///
+/// let work = future::lazy(spawn_failing).and_then(|handle| {
+///     handle.call(()).then(|result| {
+///         match result {
+///             Err(Error::Closed(err)) => assert_eq!(err.to_string(),"oops"),
+///             other => panic!("expected Error::Closed, got {:?}",other),
+///         }
+///         Ok(())
+///     })
+/// });
+///
+/// tokio::run(work);
+/// # }
+/// ```
+///
+/// ## panics
+///
+/// This function will panic if called outside of an event-loop.
+///
+pub fn spawn<Srv,Req,Rsp>(service: Srv) -> Handle<Req,Rsp>
+        where Srv: Service<Req,Rsp> + Send + 'static, Srv::Future: Send + 'static,
+              Srv::Error: error::Error + Send + Sync + 'static,
+              Req: Send + 'static, Rsp: Send + 'static {
+    let (tx,rx) = mpsc::unbounded();
+    let poison = Poison::new();
+    let handle = Handle::new(Channel(tx),poison.clone(),Limit::unlimited(),Limit::unlimited());
+    run_worker(service,rx,poison);
+    handle
+}
+
+
+/// Spawn a service to the event-loop with a bounded request queue.
+///
+/// Identical to [`spawn`](fn.spawn.html), except the worker's queue is capped at
+/// `capacity` pending requests. Once full, callers can exert backpressure by
+/// checking [`Handle::poll_ready`](struct.Handle.html#method.poll_ready) before
+/// issuing a `call`, rather than buffering requests unboundedly in the event-loop.
+///
+/// ```
+/// extern crate tokio_util;
+/// extern crate tokio;
+///
+/// use tokio_util::service::{self,Error};
+/// use tokio_util::Never;
+/// use tokio::prelude::*;
+///
+/// # fn main() {
+/// let spawn_it = || Ok(service::spawn_bounded(move |_: ()| -> Result<(),Never> { Ok(()) },1));
+///
+/// let work = future::lazy(spawn_it).and_then(|handle| {
+///     // the first call reserves the queue's only slot...
+///     assert!(handle.try_call(()).is_ok());
+///     // ...so a second call issued before the worker has had a chance to run
+///     // and dequeue the first one finds the queue still full.
+///     match handle.try_call(()) {
+///         Err(Error::Saturated(())) => {},
+///         Ok(_) => panic!("expected Error::Saturated, got Ok"),
+///         Err(other) => panic!("expected Error::Saturated, got {:?}",other),
+///     }
+///     Ok(())
+/// });
+///
+/// tokio::run(work);
+/// # }
+/// ```
+///
+/// ## panics
+///
+/// This function will panic if called outside of an event-loop.
+///
+pub fn spawn_bounded<Srv,Req,Rsp>(service: Srv, capacity: usize) -> Handle<Req,Rsp>
+        where Srv: Service<Req,Rsp> + Send + 'static, Srv::Future: Send + 'static,
+              Srv::Error: error::Error + Send + Sync + 'static,
+              Req: Send + 'static, Rsp: Send + 'static {
+    let (tx,rx) = mpsc::unbounded();
+    let poison = Poison::new();
+    let handle = Handle::new(Channel(tx),poison.clone(),Limit::bounded(capacity),Limit::unlimited());
+    run_worker(service,rx,poison);
+    handle
+}
+
+
+/// Spawn a service that coalesces requests into batches.
+///
+/// Unlike `spawn`, the service here is called with a `Vec<Req>` and must resolve
+/// with a `Vec<Rsp>` of matching length, one response per queued request, in order.
+/// A pending batch flushes to the service as soon as either trigger fires:
+///
+///   - it reaches `max_batch_size` requests, or
+///   - `max_delay` elapses since the first request of the batch arrived.
+///
+/// This amortizes per-call overhead for services whose cost is mostly independent
+/// of batch size (crypto signature verification, DB multi-get, and similar).
+///
+/// If a flushed batch's response `Vec` does not match the batch's length, every
+/// queued call in that batch fails with `Error::Closed`, wrapping a
+/// `BatchSizeMismatch`.
+///
+/// ```
+/// extern crate tokio_util;
+/// extern crate tokio;
+///
+/// use tokio_util::service;
+/// use tokio_util::Never;
+/// use tokio::prelude::*;
+/// use std::time::Duration;
+///
+/// # fn main() {
+/// // a size trigger of 3 with a very long delay means this only ever
+/// // flushes once 3 calls have been coalesced into one `Vec<i32>` call.
+/// let spawn_it = || Ok(service::spawn_batched(
+///     |reqs: Vec<i32>| -> Result<_,Never> { Ok(reqs.into_iter().map(|n| n * 2).collect::<Vec<_>>()) },
+///     3,
+///     Duration::from_secs(60),
+/// ));
+///
+/// let work = future::lazy(spawn_it).and_then(|handle| {
+///     let calls = vec![handle.call(1),handle.call(2),handle.call(3)];
+///     future::collect(calls).then(|result| {
+///         assert_eq!(result.unwrap(),vec![2,4,6]);
+///         Ok(())
+///     })
+/// });
+///
+/// tokio::run(work);
+/// # }
+/// ```
+///
 /// ## panics
 ///
 /// This function will panic if called outside of an event-loop.
 ///
-pub fn spawn<Srv,Req,Rsp>(mut service: Srv) -> Handle<Req,Rsp>
-        where Srv: Service<Req,Rsp,Error=()> + Send + 'static, Srv::Future: Send + 'static,
+pub fn spawn_batched<Srv,Req,Rsp>(service: Srv, max_batch_size: usize, max_delay: Duration) -> Handle<Req,Rsp>
+        where Srv: Service<Vec<Req>,Vec<Rsp>> + Send + 'static, Srv::Future: Send + 'static,
+              Srv::Error: error::Error + Send + Sync + 'static,
               Req: Send + 'static, Rsp: Send + 'static {
     let (tx,rx) = mpsc::unbounded();
-    let handle = Handle::new(tx);
+    let poison = Poison::new();
+    let handle = Handle::new(Channel(tx),poison.clone(),Limit::unlimited(),Limit::unlimited());
+    let worker = BatchWorker {
+        service, rx, poison, max_batch_size, max_delay,
+        pending: Vec::new(),
+        timer: None,
+    };
+    tokio::spawn(worker);
+    handle
+}
+
+
+/// Drives a batching service, accumulating `Call`s and flushing them to the
+/// service as a single `Vec<Req>` once a size or delay trigger fires.
+struct BatchWorker<Srv,Req,Rsp> {
+    service: Srv,
+    rx: mpsc::UnboundedReceiver<Call<Req,Rsp>>,
+    poison: Poison,
+    max_batch_size: usize,
+    max_delay: Duration,
+    pending: Vec<Call<Req,Rsp>>,
+    timer: Option<Delay>,
+}
+
+
+impl<Srv,Req,Rsp> BatchWorker<Srv,Req,Rsp>
+        where Srv: Service<Vec<Req>,Vec<Rsp>>, Srv::Future: Send + 'static,
+              Srv::Error: error::Error + Send + Sync + 'static,
+              Req: Send + 'static, Rsp: Send + 'static {
+
+    /// Flush the pending batch (if any) to the service, fanning its responses
+    /// back out to each call's oneshot.
+    fn flush(&mut self) {
+        self.timer = None;
+        let batch = mem::take(&mut self.pending);
+        if batch.is_empty() { return; }
+
+        // batched handles never carry a concurrency-limit permit, and any queue-slot
+        // permit was already released when the call was pulled off `rx`; `spawn_limited`
+        // and `spawn_batched` are independent modes.
+        let (reqs,txs): (Vec<_>,Vec<_>) = batch.into_iter()
+            .map(|Call { req, tx, queue_permit: _, limit_permit: _ }| (req,tx))
+            .unzip();
+
+        if self.poison.get().is_some() {
+            // the service already failed; don't hand it another batch. Dropping
+            // each `tx` here resolves its call with `Error::Closed`, via its own
+            // `Handle`'s already-poisoned state.
+            return;
+        }
+
+        let expected = txs.len();
+        let poison = self.poison.clone();
+        let work = self.service.call(reqs).then(move |result| {
+            match result {
+                Ok(rsps) => if rsps.len() == expected {
+                    for (tx,rsp) in txs.into_iter().zip(rsps) { let _ = tx.send(rsp); }
+                } else {
+                    poison.set(BatchSizeMismatch { expected, got: rsps.len() });
+                },
+                Err(err) => poison.set(err),
+            }
+            Ok(())
+        });
+        tokio::spawn(work);
+    }
+}
+
+
+impl<Srv,Req,Rsp> Future for BatchWorker<Srv,Req,Rsp>
+        where Srv: Service<Vec<Req>,Vec<Rsp>>, Srv::Future: Send + 'static,
+              Srv::Error: error::Error + Send + Sync + 'static,
+              Req: Send + 'static, Rsp: Send + 'static {
+
+    type Item = ();
+
+    type Error = ();
+
+    fn poll(&mut self) -> Poll<(),()> {
+        loop {
+            match self.rx.poll()? {
+                Async::Ready(Some(mut call)) => {
+                    // the slot this call reserved in the queue is freed as soon as it's
+                    // picked up here, not once its batch is actually flushed to the service.
+                    drop(call.queue_permit.take());
+                    if self.pending.is_empty() {
+                        self.timer = Some(Delay::new(Instant::now() + self.max_delay));
+                    }
+                    self.pending.push(call);
+                    if self.pending.len() >= self.max_batch_size {
+                        self.flush();
+                    }
+                },
+                Async::Ready(None) => {
+                    // flush whatever was queued but hadn't hit a size/delay trigger yet --
+                    // otherwise those calls would silently resolve with `Error::Canceled`
+                    // instead of actually being served.
+                    self.flush();
+                    return Ok(Async::Ready(()));
+                },
+                Async::NotReady => break,
+            }
+        }
+
+        let expired = match self.timer {
+            Some(ref mut timer) => match timer.poll() {
+                Ok(Async::NotReady) => false,
+                Ok(Async::Ready(())) | Err(_) => true,
+            },
+            None => false,
+        };
+        if expired { self.flush(); }
+
+        Ok(Async::NotReady)
+    }
+}
+
+
+/// Spawn a service whose concurrent, in-flight calls are capped by a counting semaphore.
+///
+/// Identical to [`spawn`](fn.spawn.html), except each `Handle::call` first acquires a
+/// permit, waiting if necessary, before its request is dispatched to the worker — at
+/// most `max_in_flight` service futures run concurrently. The permit is held for the
+/// lifetime of the service future and released once its response is delivered, or
+/// immediately if the call never makes it to the worker (e.g. the service has failed),
+/// so it can never leak. Use [`Handle::try_call`](struct.Handle.html#method.try_call)
+/// to fail fast instead of waiting when the limiter is saturated.
+///
+/// This protects downstream resources (connection pools, CPU-bound work) that the
+/// service guards, without hand-rolling gating around a plain `spawn`.
+///
+/// ```
+/// extern crate tokio_util;
+/// extern crate tokio;
+///
+/// use tokio_util::service::{self,Error};
+/// use tokio_util::Never;
+/// use tokio::prelude::*;
+///
+/// # fn main() {
+/// let spawn_it = || Ok(service::spawn_limited(move |_: ()| -> Result<(),Never> { Ok(()) },1));
+///
+/// let work = future::lazy(spawn_it).and_then(|handle| {
+///     // the only permit is granted to this `try_call` immediately...
+///     let first = handle.try_call(()).unwrap();
+///     // ...so a second, concurrent `try_call` before it resolves and
+///     // releases the permit finds the limiter saturated.
+///     match handle.try_call(()) {
+///         Err(Error::Saturated(())) => {},
+///         Ok(_) => panic!("expected Error::Saturated, got Ok"),
+///         Err(other) => panic!("expected Error::Saturated, got {:?}",other),
+///     }
+///     first.then(|result| { assert!(result.is_ok()); Ok(()) })
+/// });
+///
+/// tokio::run(work);
+/// # }
+/// ```
+///
+/// ## panics
+///
+/// This function will panic if called outside of an event-loop.
+///
+pub fn spawn_limited<Srv,Req,Rsp>(service: Srv, max_in_flight: usize) -> Handle<Req,Rsp>
+        where Srv: Service<Req,Rsp> + Send + 'static, Srv::Future: Send + 'static,
+              Srv::Error: error::Error + Send + Sync + 'static,
+              Req: Send + 'static, Rsp: Send + 'static {
+    let (tx,rx) = mpsc::unbounded();
+    let poison = Poison::new();
+    let handle = Handle::new(Channel(tx),poison.clone(),Limit::unlimited(),Limit::bounded(max_in_flight));
+    run_worker(service,rx,poison);
+    handle
+}
+
+
+/// A counting semaphore used to cap queued or in-flight calls.
+///
+/// Permits are acquired by [`Limit::acquire`](struct.Limit.html#method.acquire) /
+/// [`Limit::try_acquire`](struct.Limit.html#method.try_acquire) and released when the
+/// returned `Permit` is dropped.
+struct Semaphore {
+    state: Mutex<SemaphoreState>,
+}
+
+
+struct SemaphoreState {
+    permits: usize,
+    next_waiter: u64,
+    waiters: VecDeque<(u64,Task)>,
+    // ids already popped from `waiters` and handed a permit directly by
+    // `grant_next`, but not yet claimed by their owning `poll_acquire`.
+    granted: Vec<u64>,
+}
+
+
+impl Semaphore {
+
+    fn new(permits: usize) -> Self {
+        let state = SemaphoreState { permits, next_waiter: 0, waiters: VecDeque::new(), granted: Vec::new() };
+        Semaphore { state: Mutex::new(state) }
+    }
+
+    /// Poll for a permit. `waiter` tracks this caller's slot in line across
+    /// repeated polls: `None` until the first `NotReady`, after which it holds
+    /// this caller's id so a re-poll updates its parked `Task` in place rather
+    /// than queuing a duplicate entry.
+    ///
+    /// Permits are handed to a queued waiter directly, by id (see `grant_next`),
+    /// rather than by bumping `permits` and hoping the woken task wins the race
+    /// to claim it -- a concurrent `try_acquire`, or a fresh `poll_acquire` with
+    /// no waiter of its own, could otherwise steal a permit released specifically
+    /// for whichever waiter was at the front of the line, stranding it forever.
+    fn poll_acquire(&self, waiter: &mut Option<u64>) -> Poll<(),Never> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(id) = *waiter {
+            if let Some(pos) = state.granted.iter().position(|&granted| granted == id) {
+                state.granted.remove(pos);
+                *waiter = None;
+                return Ok(Async::Ready(()));
+            }
+            if let Some(entry) = state.waiters.iter_mut().find(|(wid,_)| *wid == id) {
+                // still waiting; re-park under the same slot in case we were
+                // polled by a different task than last time.
+                entry.1 = task::current();
+                return Ok(Async::NotReady);
+            }
+            // unreachable in practice (every id is either still queued, granted, or
+            // has already been claimed/cancelled by its own owner), but re-register
+            // rather than parking untracked forever if it ever does happen.
+        }
+        if state.permits > 0 && state.waiters.is_empty() {
+            state.permits -= 1;
+            *waiter = None;
+            return Ok(Async::Ready(()));
+        }
+        let id = state.next_waiter;
+        state.next_waiter = state.next_waiter.wrapping_add(1);
+        state.waiters.push_back((id,task::current()));
+        *waiter = Some(id);
+        Ok(Async::NotReady)
+    }
+
+    /// Drop a waiter's slot without it ever having acquired a permit (its
+    /// `Acquire` was dropped while parked, or after being granted a permit but
+    /// before polling again to claim it). In the latter case the permit is
+    /// still owed to someone -- hand it to the next waiter instead of leaking it.
+    fn cancel(&self, waiter: u64) {
+        let mut state = self.state.lock().unwrap();
+        if let Some(pos) = state.granted.iter().position(|&granted| granted == waiter) {
+            state.granted.remove(pos);
+            Self::grant_next(&mut state);
+        } else {
+            state.waiters.retain(|(wid,_)| *wid != waiter);
+        }
+    }
+
+    /// Fast-path, non-queuing acquire. Never jumps ahead of a waiter already in
+    /// line -- otherwise it could repeatedly steal permits meant for whoever is
+    /// queued at the front, the same race `poll_acquire`/`release` must avoid.
+    fn try_acquire(&self) -> Option<()> {
+        let mut state = self.state.lock().unwrap();
+        if state.permits > 0 && state.waiters.is_empty() {
+            state.permits -= 1;
+            Some(())
+        } else {
+            None
+        }
+    }
+
+    fn release(&self) {
+        let mut state = self.state.lock().unwrap();
+        Self::grant_next(&mut state);
+    }
+
+    /// Hand a just-freed permit to the waiter at the front of the line, by id,
+    /// or return it to the pool if nobody is waiting.
+    fn grant_next(state: &mut SemaphoreState) {
+        match state.waiters.pop_front() {
+            Some((id,task)) => {
+                state.granted.push(id);
+                task.notify();
+            },
+            None => state.permits += 1,
+        }
+    }
+}
+
+
+impl fmt::Debug for Semaphore {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let permits = self.state.lock().unwrap().permits;
+        f.debug_struct("Semaphore").field("available_permits",&permits).finish()
+    }
+}
+
+
+/// A permit acquired from a `Semaphore`; releases it back on drop.
+struct Permit(Arc<Semaphore>);
+
+
+impl fmt::Debug for Permit {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { f.write_str("Permit") }
+}
+
+
+impl Drop for Permit {
+
+    fn drop(&mut self) { self.0.release(); }
+}
+
+
+/// A cap on a `Handle`'s queued or in-flight calls; `None` for no cap.
+#[derive(Debug,Clone)]
+struct Limit(Option<Arc<Semaphore>>);
+
+
+impl Limit {
+
+    fn unlimited() -> Self { Limit(None) }
+
+    fn bounded(max_in_flight: usize) -> Self { Limit(Some(Arc::new(Semaphore::new(max_in_flight)))) }
+
+    fn acquire(&self) -> Acquire { Acquire { sem: self.0.clone(), waiter: None } }
+
+    /// Acquire a permit without waiting; `None` if the limit is saturated.
+    fn try_acquire(&self) -> Option<Option<Permit>> {
+        match &self.0 {
+            None => Some(None),
+            Some(sem) => sem.try_acquire().map(|()| Some(Permit(sem.clone()))),
+        }
+    }
+}
+
+
+/// Future returned by `Limit::acquire`; resolves once a permit is available
+/// (or immediately, with no permit, for an unlimited `Limit`).
+struct Acquire {
+    sem: Option<Arc<Semaphore>>,
+    waiter: Option<u64>,
+}
+
+
+impl Future for Acquire {
+
+    type Item = Option<Permit>;
+
+    type Error = Never;
+
+    fn poll(&mut self) -> Poll<Option<Permit>,Never> {
+        match &self.sem {
+            None => Ok(Async::Ready(None)),
+            Some(sem) => sem.poll_acquire(&mut self.waiter).map(|ready| ready.map(|()| Some(Permit(sem.clone())))),
+        }
+    }
+}
+
+
+impl Drop for Acquire {
+
+    /// If this `Acquire` is dropped while still parked, deregister its
+    /// waiter slot so a subsequent `release` can't strand a permit on it.
+    fn drop(&mut self) {
+        if let (Some(sem),Some(waiter)) = (&self.sem,self.waiter.take()) {
+            sem.cancel(waiter);
+        }
+    }
+}
+
+
+/// Drive `service` against whatever requests arrive on `rx`, spawning each
+/// call's future onto the event-loop and poisoning `poison` if one fails.
+fn run_worker<Srv,Req,Rsp,Rx>(mut service: Srv, rx: Rx, poison: Poison)
+        where Srv: Service<Req,Rsp> + Send + 'static, Srv::Future: Send + 'static,
+              Srv::Error: error::Error + Send + Sync + 'static,
+              Rx: Stream<Item=Call<Req,Rsp>,Error=()> + Send + 'static,
+              Req: Send + 'static, Rsp: Send + 'static {
     let serve = move |call: Call<_,_>| {
-        let Call { req, tx } = call;
-        let work = service.call(req).and_then(move |rsp| {
-            let _ = tx.send(rsp);
+        let Call { req, tx, queue_permit, limit_permit } = call;
+        drop(queue_permit);
+        if poison.get().is_some() {
+            // the service already failed; don't hand it another request. Dropping
+            // `tx` here resolves the caller's `call` with `Error::Closed`, via its
+            // own `Handle`'s already-poisoned state.
+            return;
+        }
+        let poison = poison.clone();
+        let work = service.call(req).then(move |result| {
+            match result {
+                Ok(rsp) => { let _ = tx.send(rsp); },
+                Err(err) => poison.set(err),
+            }
+            drop(limit_permit);
             Ok(())
         });
         tokio::spawn(work);
@@ -92,7 +664,6 @@ pub fn spawn<Srv,Req,Rsp>(mut service: Srv) -> Handle<Req,Rsp>
 
     let work = rx.map(serve).for_each(|()| Ok(()));
     tokio::spawn(work);
-    handle
 }
 
 
@@ -127,11 +698,17 @@ impl<F,T,Req> Service<Req,T::Item> for F where F: FnMut(Req) -> T, T: IntoFuture
 struct Call<Req,Rsp> {
     req: Req,
     tx: oneshot::Sender<Rsp>,
+    /// Reserves this call's place in the worker's queue; released as soon as
+    /// the worker picks the call up, freeing the slot for the next enqueuer.
+    queue_permit: Option<Permit>,
+    /// Reserves this call's place among the service's concurrent in-flight
+    /// calls; released once the service's future resolves.
+    limit_permit: Option<Permit>,
 }
 
 
 /// Cloneable handle to a spawned service.
-/// 
+///
 /// Allows one or more tasks to asynchronously call a service.
 /// See module-level docs for example usage.
 ///
@@ -140,7 +717,17 @@ struct Call<Req,Rsp> {
 ///
 #[derive(Debug)]
 pub struct Handle<Req,Rsp> {
-    inner: mpsc::UnboundedSender<Call<Req,Rsp>>,
+    inner: Channel<Call<Req,Rsp>>,
+    poison: Poison,
+    /// Caps how many calls may sit in the worker's queue, waiting to be picked up.
+    queue: Limit,
+    /// Caps how many service calls may run concurrently.
+    limit: Limit,
+    /// A queue-slot permit reserved by `poll_ready`, waiting to be picked up by
+    /// the next `call`/`try_call`. Shared across clones: whichever one reserves
+    /// a slot, any of them may spend it, matching the single shared queue they
+    /// all dispatch into.
+    reserved: Arc<Mutex<Reservation>>,
 }
 
 
@@ -148,32 +735,188 @@ impl<Req,Rsp> Clone for Handle<Req,Rsp> {
 
     fn clone(&self) -> Self {
         let inner = self.inner.clone();
-        Self { inner }
+        let poison = self.poison.clone();
+        let queue = self.queue.clone();
+        let limit = self.limit.clone();
+        let reserved = self.reserved.clone();
+        Self { inner, poison, queue, limit, reserved }
     }
 }
 
 
 impl<Req,Rsp> Handle<Req,Rsp> {
 
-    fn new(inner: mpsc::UnboundedSender<Call<Req,Rsp>>) -> Self { Self { inner } }
+    fn new(inner: Channel<Call<Req,Rsp>>, poison: Poison, queue: Limit, limit: Limit) -> Self {
+        let reserved = Arc::new(Mutex::new(Reservation::Idle));
+        Self { inner, poison, queue, limit, reserved }
+    }
 
-    /// Execute a call against the associated service
+    /// Poll whether the handle has capacity to issue another `call`.
+    ///
+    /// Handles returned by [`spawn`](fn.spawn.html)/[`spawn_limited`](fn.spawn_limited.html)
+    /// (no queue cap) always report ready. Handles returned by
+    /// [`spawn_bounded`](fn.spawn_bounded.html) resolve `NotReady` while the worker's
+    /// queue is at capacity, letting callers exert backpressure instead of buffering
+    /// requests without limit.
+    ///
+    /// Unlike a bare capacity check, `Ready` here is a genuine reservation: the slot
+    /// it grants is held by this `Handle` (shared with its clones) until the very next
+    /// `call`/`try_call` picks it up, so that call can never fail merely because the
+    /// queue filled up in between -- at worst it finds the reservation already
+    /// waiting for it. Call this immediately before the `call`/`try_call` it's meant
+    /// to guard; an unused reservation just holds its slot open until one comes along.
+    pub fn poll_ready(&self) -> Poll<(),Error<Req>> {
+        if let Some(err) = self.poison.get() { return Err(Error::Closed(err)); }
+        let mut reserved = self.reserved.lock().unwrap();
+        loop {
+            match mem::replace(&mut *reserved, Reservation::Idle) {
+                Reservation::Granted(permit) => {
+                    *reserved = Reservation::Granted(permit);
+                    return Ok(Async::Ready(()));
+                },
+                Reservation::Pending(mut acquire) => match acquire.poll() {
+                    Ok(Async::Ready(permit)) => *reserved = Reservation::Granted(permit),
+                    Ok(Async::NotReady) => {
+                        *reserved = Reservation::Pending(acquire);
+                        return Ok(Async::NotReady);
+                    },
+                    Err(never) => match never { },
+                },
+                Reservation::Idle => *reserved = Reservation::Pending(self.queue.acquire()),
+            }
+        }
+    }
+
+    /// Execute a call against the associated service.
+    ///
+    /// If the handle is queue-bounded (see [`spawn_bounded`](fn.spawn_bounded.html)),
+    /// this waits for a queue slot -- reused from a preceding `poll_ready` if one is
+    /// already reserved, acquired fresh otherwise -- before the request is enqueued.
+    /// If the handle is concurrency-limited (see
+    /// [`spawn_limited`](fn.spawn_limited.html)), this also waits for a permit to
+    /// become available before dispatching the request. Use
+    /// [`try_call`](#method.try_call) to fail fast instead.
     pub fn call(&self, req: Req) -> impl Future<Item=Rsp,Error=Error<Req>> {
+        let this = self.clone();
+        self.take_reservation().join(self.limit.acquire()).from_err()
+            .and_then(move |(queue_permit,limit_permit)| this.dispatch(req,queue_permit,limit_permit))
+    }
+
+    /// Like [`call`](#method.call), but fails immediately with `Error::Saturated`
+    /// instead of waiting, if the handle's queue or concurrency limit (whichever
+    /// it has) is already at capacity. Handles with no such limit never saturate.
+    pub fn try_call(&self, req: Req) -> Result<impl Future<Item=Rsp,Error=Error<Req>>,Error<Req>> {
+        let queue_permit = match self.try_take_reservation() {
+            Some(permit) => permit,
+            None => return Err(Error::Saturated(req)),
+        };
+        match self.limit.try_acquire() {
+            Some(limit_permit) => Ok(self.dispatch(req,queue_permit,limit_permit)),
+            None => Err(Error::Saturated(req)),
+        }
+    }
+
+    /// Take whatever reservation `poll_ready` has already made (granted or still
+    /// pending), or start a fresh one if none is in flight.
+    fn take_reservation(&self) -> Either<future::FutureResult<Option<Permit>,Never>,Acquire> {
+        let mut reserved = self.reserved.lock().unwrap();
+        match mem::replace(&mut *reserved, Reservation::Idle) {
+            Reservation::Granted(permit) => Either::A(future::ok(permit)),
+            Reservation::Pending(acquire) => Either::B(acquire),
+            Reservation::Idle => Either::B(self.queue.acquire()),
+        }
+    }
+
+    /// Like [`take_reservation`](#method.take_reservation), but never waits: a
+    /// reservation still `Pending` doesn't count as available yet.
+    fn try_take_reservation(&self) -> Option<Option<Permit>> {
+        let mut reserved = self.reserved.lock().unwrap();
+        match mem::replace(&mut *reserved, Reservation::Idle) {
+            Reservation::Granted(permit) => Some(permit),
+            pending @ Reservation::Pending(_) => { *reserved = pending; None },
+            Reservation::Idle => self.queue.try_acquire(),
+        }
+    }
+
+    fn dispatch(&self, req: Req, queue_permit: Option<Permit>, limit_permit: Option<Permit>) -> impl Future<Item=Rsp,Error=Error<Req>> {
         let (tx,rx) = oneshot::channel();
-        let call = Call { req, tx };
-        self.inner.unbounded_send(call).into_future().from_err()
-            .and_then(move |()| rx.from_err())
+        let call = Call { req, tx, queue_permit, limit_permit };
+        let send_poison = self.poison.clone();
+        let recv_poison = self.poison.clone();
+        // Keep a sender clone alive for the life of the returned future, not just
+        // through this synchronous send -- `call`'s own `Handle` clone is dropped the
+        // moment this method returns, and if it (or whichever clone dispatched this
+        // request) was the worker's last live sender, the channel closes out from
+        // under this still-in-flight request. `spawn_batched` in particular force-
+        // flushes and tears itself down the instant it observes that, so a request
+        // that outlives every `Handle` would otherwise be served as its own
+        // batch-of-one instead of waiting to coalesce with others.
+        let inner = self.inner.clone();
+        self.inner.try_send(call)
+            .map_err(move |Call { req, .. }| send_poison.closed_or(Error::SendError(req)))
+            .into_future()
+            .and_then(move |()| rx.map_err(move |_| recv_poison.closed_or(Error::Canceled)))
+            .then(move |result| { drop(inner); result })
+    }
+}
+
+
+/// Pending state of a `Handle`'s queue-slot reservation: idle until `poll_ready`
+/// starts one, then waiting on the underlying `Acquire`, then sitting `Granted`
+/// until `call`/`try_call` spends it.
+enum Reservation {
+    Idle,
+    Pending(Acquire),
+    Granted(Option<Permit>),
+}
+
+
+impl fmt::Debug for Reservation {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let state = match self {
+            Reservation::Idle => "idle",
+            Reservation::Pending(_) => "pending",
+            Reservation::Granted(_) => "granted",
+        };
+        f.debug_tuple("Reservation").field(&state).finish()
+    }
+}
+
+
+/// Thin wrapper around the worker's sender. Queue depth is enforced by a
+/// `Handle`'s `queue` semaphore rather than by the channel itself, so every
+/// `Handle` -- bounded or not -- is backed by the same unbounded sender.
+#[derive(Debug)]
+struct Channel<T>(mpsc::UnboundedSender<T>);
+
+
+impl<T> Clone for Channel<T> {
+
+    fn clone(&self) -> Self { Channel(self.0.clone()) }
+}
+
+
+impl<T> Channel<T> {
+
+    /// Send `item`, returning it back on failure (the worker's receiver dropped).
+    fn try_send(&self, item: T) -> Result<(),T> {
+        self.0.unbounded_send(item).map_err(|err| err.into_inner())
     }
 }
 
 
 /// Error raised by a service handle
-#[derive(Debug,Copy,Clone)]
+#[derive(Debug,Clone)]
 pub enum Error<T> {
     /// Failed to send request; service has failed.
     SendError(T),
     /// Response channel was cancelled; request has failed.
     Canceled,
+    /// The service has failed; holds the underlying error which poisoned it.
+    Closed(Arc<ServiceError>),
+    /// Returned by `Handle::try_call`: the handle's queue or concurrency limit is saturated.
+    Saturated(T),
 }
 
 
@@ -183,6 +926,8 @@ impl<T> Error<T> {
         match self {
             Error::SendError(_) => "unable to send request (receiver dropped)",
             Error::Canceled => "request cancelled (rsp channel dropped)",
+            Error::Closed(_) => "service has failed and is no longer accepting requests",
+            Error::Saturated(_) => "at capacity; no permit available without waiting",
         }
     }
 }
@@ -191,7 +936,10 @@ impl<T> Error<T> {
 impl<T> fmt::Display for Error<T> where T: fmt::Debug {
 
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        f.write_str(self.as_str())
+        match self {
+            Error::Closed(err) => write!(f,"{}: {}",self.as_str(),err),
+            _ => f.write_str(self.as_str()),
+        }
     }
 }
 
@@ -211,9 +959,139 @@ impl<Req,Rsp> From<mpsc::SendError<Call<Req,Rsp>>> for Error<Req> {
 }
 
 
+impl<T> From<Never> for Error<T> {
+
+    fn from(never: Never) -> Self { never.into() }
+}
+
+
 impl<T> From<oneshot::Canceled> for Error<T> {
 
     fn from(_: oneshot::Canceled) -> Self { Error::Canceled }
 }
 
 
+/// A batched service returned a different number of responses than the
+/// requests it was handed.
+#[derive(Debug,Copy,Clone)]
+pub struct BatchSizeMismatch {
+    expected: usize,
+    got: usize,
+}
+
+
+impl fmt::Display for BatchSizeMismatch {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,"batched service returned {} responses for a batch of {} requests",self.got,self.expected)
+    }
+}
+
+
+impl error::Error for BatchSizeMismatch {
+
+    fn description(&self) -> &str { "batched service returned the wrong number of responses" }
+}
+
+
+/// The error which poisoned a spawned service.
+///
+/// Wraps whatever error the service's future resolved to (or, for a batched
+/// service, a `BatchSizeMismatch`), type-erased, so that it can be shared with
+/// every `Handle::call` that resolves with `Error::Closed`.
+#[derive(Debug)]
+pub struct ServiceError(Box<dyn error::Error + Send + Sync>);
+
+
+impl ServiceError {
+
+    fn new<E>(err: E) -> Self where E: error::Error + Send + Sync + 'static {
+        ServiceError(Box::new(err))
+    }
+}
+
+
+impl fmt::Display for ServiceError {
+
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result { fmt::Display::fmt(&self.0,f) }
+}
+
+
+impl error::Error for ServiceError {
+
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> { self.0.source() }
+}
+
+
+/// Shared slot recording the first error to poison a worker, if any.
+#[derive(Debug,Clone)]
+struct Poison(Arc<Mutex<Option<Arc<ServiceError>>>>);
+
+
+impl Poison {
+
+    fn new() -> Self { Poison(Arc::new(Mutex::new(None))) }
+
+    /// Record `err` as the reason the worker is poisoned, unless it is
+    /// already poisoned; only the first error is kept.
+    fn set<E>(&self, err: E) where E: error::Error + Send + Sync + 'static {
+        let mut slot = self.0.lock().unwrap();
+        if slot.is_none() {
+            *slot = Some(Arc::new(ServiceError::new(err)));
+        }
+    }
+
+    /// The error which poisoned the worker, if it has failed.
+    fn get(&self) -> Option<Arc<ServiceError>> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Returns `Error::Closed` if the worker is poisoned, otherwise `fallback`.
+    fn closed_or<T>(&self, fallback: Error<T>) -> Error<T> {
+        match self.get() {
+            Some(err) => Error::Closed(err),
+            None => fallback,
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Regression test for the bug fixed alongside the `granted` list: a waiter
+    /// granted a permit (popped off `waiters`, notified) but cancelled before it
+    /// polls again to claim it must hand that permit on to the next waiter in
+    /// line, rather than leaking it.
+    #[test]
+    fn cancel_after_grant_regrants_to_next_waiter() {
+        tokio::run(future::lazy(|| -> Result<(),()> {
+            let sem = Semaphore::new(1);
+
+            // takes the only permit immediately; no contention yet.
+            let mut first = None;
+            assert_eq!(sem.poll_acquire(&mut first),Ok(Async::Ready(())));
+
+            // no permits left, so both of these park in the queue, in order.
+            let mut second = None;
+            assert_eq!(sem.poll_acquire(&mut second),Ok(Async::NotReady));
+            let mut third = None;
+            assert_eq!(sem.poll_acquire(&mut third),Ok(Async::NotReady));
+
+            // releasing the first permit hands it directly to `second` (now
+            // granted, but not yet reclaimed via a re-poll).
+            sem.release();
+
+            // cancelling `second` here -- as if its `Acquire` were dropped after
+            // being notified but before polling again -- must not strand the
+            // permit it was just given; it has to flow on to `third`.
+            sem.cancel(second.take().unwrap());
+            assert_eq!(sem.poll_acquire(&mut third),Ok(Async::Ready(())));
+
+            Ok(())
+        }));
+    }
+}
+
+